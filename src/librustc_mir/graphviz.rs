@@ -11,13 +11,44 @@
 use dot;
 use rustc::mir::repr::*;
 use rustc::middle::ty;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::io::{self, Write};
 use syntax::ast::NodeId;
+use syntax::codemap::Span;
+
+/// Options controlling which part of the CFG `write_mir_graphviz` emits.
+///
+/// The default filter emits the whole graph with no highlighting, so
+/// `write_mir_graphviz` can keep its simple signature and delegate here.
+#[derive(Clone, Default)]
+pub struct Filter {
+    /// If `Some`, only blocks reachable from these roots are emitted; `None`
+    /// emits every block.
+    pub roots: Option<Vec<BasicBlock>>,
+    /// Blocks to highlight with a distinct `bgcolor`, e.g. a path of interest.
+    pub highlight: Vec<BasicBlock>,
+}
 
 /// Write a graphviz DOT graph of a list of MIRs.
 pub fn write_mir_graphviz<'a, 't, W, I>(tcx: &ty::TyCtxt<'t>, iter: I, w: &mut W) -> io::Result<()>
 where W: Write, I: Iterator<Item=(&'a NodeId, &'a Mir<'a>)> {
+    write_mir_graphviz_filtered(tcx, iter, &Filter::default(), w)
+}
+
+/// Write a graphviz DOT graph of a list of MIRs, restricted and annotated
+/// according to `filter`.
+///
+/// When `filter.roots` is set the reachable set is computed up front with a
+/// depth-first walk over `terminator.successors()`, and both the node and edge
+/// loops skip any block outside it — so a single hot path through a large
+/// function can be rendered without the surrounding noise.
+pub fn write_mir_graphviz_filtered<'a, 't, W, I>(tcx: &ty::TyCtxt<'t>,
+                                                 iter: I,
+                                                 filter: &Filter,
+                                                 w: &mut W) -> io::Result<()>
+where W: Write, I: Iterator<Item=(&'a NodeId, &'a Mir<'a>)> {
+    let highlight: HashSet<BasicBlock> = filter.highlight.iter().cloned().collect();
     for (&nodeid, mir) in iter {
         try!(writeln!(w, "digraph Mir_{} {{", nodeid));
 
@@ -29,20 +60,266 @@ where W: Write, I: Iterator<Item=(&'a NodeId, &'a Mir<'a>)> {
         // Graph label
         try!(write_graph_label(tcx, nodeid, mir, w));
 
+        // The set of blocks to emit, or `None` to emit all of them.
+        let reachable = filter.roots.as_ref().map(|roots| reachable_blocks(mir, roots));
+
+        // Loop-nesting depth per block, used to shade blocks inside hot loops.
+        let depths = loop_depths(mir);
+
         // Nodes
         for block in mir.all_basic_blocks() {
-            try!(write_node(block, mir, w));
+            if !is_emitted(&reachable, block) {
+                continue;
+            }
+            let depth = depths.get(&block).cloned().unwrap_or(0);
+            try!(write_node(tcx, block, mir, w, highlight.contains(&block), depth));
         }
 
         // Edges
         for source in mir.all_basic_blocks() {
-            try!(write_edges(source, mir, w));
+            if !is_emitted(&reachable, source) {
+                continue;
+            }
+            try!(write_edges(source, mir, &reachable, w));
         }
         try!(writeln!(w, "}}"))
     }
     Ok(())
 }
 
+/// Compute the set of basic blocks reachable from `roots` by following
+/// `terminator.successors()`.
+fn reachable_blocks(mir: &Mir, roots: &[BasicBlock]) -> HashSet<BasicBlock> {
+    let mut seen = HashSet::new();
+    let mut stack: Vec<BasicBlock> = roots.to_vec();
+    while let Some(block) = stack.pop() {
+        if seen.insert(block) {
+            for &succ in mir.basic_block_data(block).terminator().successors().iter() {
+                if !seen.contains(&succ) {
+                    stack.push(succ);
+                }
+            }
+        }
+    }
+    seen
+}
+
+/// Whether `block` should be emitted given an optional reachable set (`None`
+/// means emit everything).
+fn is_emitted(reachable: &Option<HashSet<BasicBlock>>, block: BasicBlock) -> bool {
+    reachable.as_ref().map_or(true, |set| set.contains(&block))
+}
+
+/// Compute the loop-nesting depth of every basic block in the CFG.
+///
+/// A depth-first search classifies each edge as tree/back/forward/cross by the
+/// color of its target; an edge to a block still on the DFS stack is a *back
+/// edge* `tail -> header`, which identifies a loop headed at `header`. The
+/// natural loop of each back edge is the header plus every block that can reach
+/// `tail` without passing through the header, found by walking predecessors.
+/// The natural loops of back edges that share a header are merged into one
+/// loop. A block's depth is the number of these per-header loops enclosing it,
+/// so a block in an inner loop nested in an outer loop reports depth 2.
+fn loop_depths(mir: &Mir) -> HashMap<BasicBlock, u32> {
+    #[derive(PartialEq, Clone, Copy)]
+    enum Color { White, Gray, Black }
+
+    let blocks = mir.all_basic_blocks();
+
+    // Predecessor map, derived from the successor edges.
+    let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+    for &block in &blocks {
+        for &succ in mir.basic_block_data(block).terminator().successors().iter() {
+            preds.entry(succ).or_insert_with(Vec::new).push(block);
+        }
+    }
+
+    // Iterative DFS collecting back edges. Each stack frame is a block and the
+    // index of the next successor to visit from it.
+    let mut color: HashMap<BasicBlock, Color> =
+        blocks.iter().map(|&b| (b, Color::White)).collect();
+    let mut back_edges: Vec<(BasicBlock, BasicBlock)> = Vec::new();
+    for &root in &blocks {
+        if color[&root] != Color::White {
+            continue;
+        }
+        color.insert(root, Color::Gray);
+        let mut stack: Vec<(BasicBlock, usize)> = vec![(root, 0)];
+        while let Some(&(block, next)) = stack.last() {
+            let succs = mir.basic_block_data(block).terminator().successors();
+            if next < succs.len() {
+                stack.last_mut().unwrap().1 += 1;
+                let succ = succs[next];
+                match color[&succ] {
+                    Color::White => {
+                        color.insert(succ, Color::Gray);
+                        stack.push((succ, 0));
+                    }
+                    // Target still on the stack: a back edge, i.e. a loop.
+                    Color::Gray => back_edges.push((block, succ)),
+                    // Forward or cross edge: nothing to classify for loops.
+                    Color::Black => {}
+                }
+            } else {
+                color.insert(block, Color::Black);
+                stack.pop();
+            }
+        }
+    }
+
+    // Merge the natural loops of all back edges sharing a header into one loop
+    // per header, so a multi-latch loop (two back edges to the same header)
+    // counts as a single level of nesting rather than two.
+    let mut loops: HashMap<BasicBlock, HashSet<BasicBlock>> = HashMap::new();
+    for &(tail, header) in &back_edges {
+        let body = loops.entry(header).or_insert_with(HashSet::new);
+        body.insert(header);
+        let mut worklist = Vec::new();
+        if body.insert(tail) {
+            worklist.push(tail);
+        }
+        while let Some(block) = worklist.pop() {
+            if let Some(block_preds) = preds.get(&block) {
+                for &pred in block_preds {
+                    // Inserting stops at the header, which is already present.
+                    if body.insert(pred) {
+                        worklist.push(pred);
+                    }
+                }
+            }
+        }
+    }
+
+    // A block's depth is the number of distinct (per-header) loops enclosing it.
+    let mut depth: HashMap<BasicBlock, u32> = blocks.iter().map(|&b| (b, 0)).collect();
+    for body in loops.values() {
+        for &block in body {
+            *depth.get_mut(&block).unwrap() += 1;
+        }
+    }
+
+    depth
+}
+
+/// Write a structured JSON document describing a list of MIRs.
+///
+/// Unlike the graphviz DOT output this is meant to be consumed by external CFG
+/// viewers and diffing tools rather than rendered directly, so it avoids any
+/// presentational markup. Each `Mir` becomes an object keyed by its `NodeId`
+/// containing the arg/var/temp type table and an array of basic blocks; each
+/// block carries its statements (via their `Debug` representation), the head of
+/// its terminator, and its labelled successors. It reuses the same iteration
+/// over `mir.all_basic_blocks()` and `terminator.successors()` as the DOT
+/// writer so the two stay in sync.
+pub fn write_mir_json<'a, 't, W, I>(tcx: &ty::TyCtxt<'t>, iter: I, w: &mut W) -> io::Result<()>
+where W: Write, I: Iterator<Item=(&'a NodeId, &'a Mir<'a>)> {
+    try!(write!(w, "{{"));
+    for (i, (&nodeid, mir)) in iter.enumerate() {
+        if i > 0 {
+            try!(write!(w, ","));
+        }
+        try!(write!(w, r#""{}":"#, nodeid));
+        try!(write_mir_json_body(tcx, nodeid, mir, w));
+    }
+    writeln!(w, "}}")
+}
+
+/// Serialize a single `Mir` as a JSON object (the value half of the
+/// `NodeId`-keyed map produced by `write_mir_json`).
+fn write_mir_json_body<W: Write>(tcx: &ty::TyCtxt, nid: NodeId, mir: &Mir, w: &mut W)
+-> io::Result<()> {
+    try!(write!(w, r#"{{"name":"{}","#, json_escape(&tcx.map.path_to_string(nid))));
+
+    // The arg/var/temp type table, mirroring `write_graph_label`.
+    try!(write!(w, r#""decls":{{"args":["#));
+    for (i, arg) in mir.arg_decls.iter().enumerate() {
+        if i > 0 {
+            try!(write!(w, ","));
+        }
+        try!(write!(w, r#"{{"lvalue":"{:?}","ty":"{}"}}"#,
+                    Lvalue::Arg(i as u32), json_escape(&format!("{:?}", arg.ty))));
+    }
+    try!(write!(w, r#"],"vars":["#));
+    for (i, var) in mir.var_decls.iter().enumerate() {
+        if i > 0 {
+            try!(write!(w, ","));
+        }
+        try!(write!(w, r#"{{"lvalue":"{:?}","ty":"{}","mut":{},"name":"{}"}}"#,
+                    Lvalue::Var(i as u32), json_escape(&format!("{:?}", var.ty)),
+                    var.mutability == Mutability::Mut, json_escape(&format!("{}", var.name))));
+    }
+    try!(write!(w, r#"],"temps":["#));
+    for (i, temp) in mir.temp_decls.iter().enumerate() {
+        if i > 0 {
+            try!(write!(w, ","));
+        }
+        try!(write!(w, r#"{{"lvalue":"{:?}","ty":"{}"}}"#,
+                    Lvalue::Temp(i as u32), json_escape(&format!("{:?}", temp.ty))));
+    }
+    try!(write!(w, r#"],"return":"{}"}},"#, match mir.return_ty {
+        ty::FnOutput::FnConverging(ty) => json_escape(&format!("{:?}", ty)),
+        ty::FnOutput::FnDiverging => "!".to_string(),
+    }));
+
+    // One object per basic block, in `all_basic_blocks()` order.
+    try!(write!(w, r#""basic_blocks":["#));
+    for (i, block) in mir.all_basic_blocks().into_iter().enumerate() {
+        if i > 0 {
+            try!(write!(w, ","));
+        }
+        try!(write_block_json(block, mir, w));
+    }
+
+    write!(w, "]}}")
+}
+
+/// Serialize a single basic block: its index, statements, terminator head, and
+/// labelled successor edges.
+fn write_block_json<W: Write>(block: BasicBlock, mir: &Mir, w: &mut W) -> io::Result<()> {
+    let data = mir.basic_block_data(block);
+
+    try!(write!(w, r#"{{"block":{},"statements":["#, block.index()));
+    for (i, statement) in data.statements.iter().enumerate() {
+        if i > 0 {
+            try!(write!(w, ","));
+        }
+        try!(write!(w, r#""{}""#, json_escape(&format!("{:?}", statement))));
+    }
+    try!(write!(w, "],"));
+
+    let terminator = data.terminator();
+    let mut terminator_head = String::new();
+    terminator.fmt_head(&mut terminator_head).unwrap();
+    try!(write!(w, r#""terminator":"{}","successors":["#, json_escape(&terminator_head)));
+
+    let labels = terminator.fmt_successor_labels();
+    for (i, (&target, label)) in terminator.successors().iter().zip(labels).enumerate() {
+        if i > 0 {
+            try!(write!(w, ","));
+        }
+        try!(write!(w, r#"{{"block":{},"label":"{}"}}"#, target.index(), json_escape(&label)));
+    }
+
+    write!(w, "]}}")
+}
+
+/// Escape a string for inclusion in a JSON string literal.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 /// Write a graphviz HTML-styled label for the given basic block, with
 /// all necessary escaping already performed. (This is suitable for
 /// emitting directly, as is done in this module, or for use with the
@@ -60,25 +337,52 @@ pub fn write_node_label<W: Write, INIT, FINI>(block: BasicBlock,
           FINI: Fn(&mut W) -> io::Result<()>
 {
     let data = mir.basic_block_data(block);
+    // Default rendering: gray header and a single statements cell, matching the
+    // original output this public entry point has always produced.
+    write_label_table(block, mir, w, num_cols, "gray", init, |w| {
+        if !data.statements.is_empty() {
+            try!(write!(w, r#"<tr><td align="left" balign="left">"#));
+            for statement in &data.statements {
+                try!(write!(w, "{}<br/>", escape(statement)));
+            }
+            try!(write!(w, "</td></tr>"));
+        }
+        Ok(())
+    }, fini)
+}
+
+/// Emit the shared pseudo-HTML table scaffolding for a basic block label: the
+/// `<table>` preamble, the `bgcolor`-shaded block-number row, the caller's
+/// statement rows, the terminator-head row, and the close. `init`/`fini` add
+/// rows before and after the body, and `stmts` renders the middle section, so
+/// both the public `write_node_label` and this module's own node writer share
+/// one copy of the table format.
+fn write_label_table<W: Write, INIT, STMTS, FINI>(block: BasicBlock,
+                                                  mir: &Mir,
+                                                  w: &mut W,
+                                                  num_cols: u32,
+                                                  bgcolor: &str,
+                                                  init: INIT,
+                                                  stmts: STMTS,
+                                                  fini: FINI) -> io::Result<()>
+    where INIT: Fn(&mut W) -> io::Result<()>,
+          STMTS: Fn(&mut W) -> io::Result<()>,
+          FINI: Fn(&mut W) -> io::Result<()>
+{
+    let data = mir.basic_block_data(block);
 
     try!(write!(w, r#"<table border="0" cellborder="1" cellspacing="0">"#));
 
-    // Basic block number at the top.
-    try!(write!(w, r#"<tr><td {attrs} colspan="{colspan}">{blk}</td></tr>"#,
-                attrs=r#"bgcolor="gray" align="center""#,
+    // Basic block number at the top, shaded `bgcolor` (gray by default).
+    try!(write!(w, r#"<tr><td bgcolor="{bg}" align="center" colspan="{colspan}">{blk}</td></tr>"#,
+                bg=bgcolor,
                 colspan=num_cols,
                 blk=block.index()));
 
     try!(init(w));
 
-    // List of statements in the middle.
-    if !data.statements.is_empty() {
-        try!(write!(w, r#"<tr><td align="left" balign="left">"#));
-        for statement in &data.statements {
-            try!(write!(w, "{}<br/>", escape(statement)));
-        }
-        try!(write!(w, "</td></tr>"));
-    }
+    // Statements in the middle, rendered by the caller.
+    try!(stmts(w));
 
     // Terminator head at the bottom, not including the list of successor blocks. Those will be
     // displayed as labels on the edges between blocks.
@@ -93,26 +397,138 @@ pub fn write_node_label<W: Write, INIT, FINI>(block: BasicBlock,
 }
 
 /// Write a graphviz DOT node for the given basic block.
-fn write_node<W: Write>(block: BasicBlock, mir: &Mir, w: &mut W) -> io::Result<()> {
-    // Start a new node with the label to follow, in one of DOT's pseudo-HTML tables.
-    try!(write!(w, r#"    {} [shape="none", label=<"#, node(block)));
-    try!(write_node_label(block, mir, w, 1, |_| Ok(()), |_| Ok(())));
+fn write_node<W: Write>(tcx: &ty::TyCtxt,
+                        block: BasicBlock,
+                        mir: &Mir,
+                        w: &mut W,
+                        highlight: bool,
+                        depth: u32)
+-> io::Result<()> {
+    let data = mir.basic_block_data(block);
+    // Start a new node with the label to follow, in one of DOT's pseudo-HTML tables. Attach a
+    // node-level `URL`/`tooltip` from the first statement's span so the block as a whole is
+    // clickable even where individual cell links are unavailable.
+    try!(write!(w, r#"    {} [shape="none", {style}"#, node(block), style=node_style(data.terminator())));
+    if let Some(statement) = data.statements.first() {
+        // `URL`/`tooltip` are plain DOT attribute strings (not pseudo-HTML), so
+        // escape per DOT string rules rather than as HTML entities.
+        let link = span_to_dot_string(tcx, statement.span);
+        try!(write!(w, r#"URL="{link}", tooltip="{link}", "#, link=link));
+    }
+    try!(write!(w, "label=<"));
+    // Highlighting takes precedence; otherwise shade progressively darker with
+    // loop-nesting depth so hot inner loops stand out.
+    let bgcolor = if highlight { "orange".to_string() } else { depth_color(depth) };
+    try!(write_label_table(block, mir, w, 1, &bgcolor,
+        // An extra header row naming the loop-nesting depth, for blocks in a loop.
+        |w| {
+            if depth > 0 {
+                try!(write!(w, r#"<tr><td align="center" colspan="1">loop depth: {}</td></tr>"#,
+                            depth));
+            }
+            Ok(())
+        },
+        // One row per statement, each linking back to its own source span so the
+        // rendered SVG lets you click a statement and jump to source.
+        |w| {
+            for statement in &data.statements {
+                try!(write!(w, r#"<tr><td align="left" balign="left" {span}>{stmt}</td></tr>"#,
+                            span=span_attrs(tcx, statement.span),
+                            stmt=escape(statement)));
+            }
+            Ok(())
+        },
+        |_| Ok(())));
     // Close the node label and the node itself.
     writeln!(w, ">];")
 }
 
+/// Distinct node styling for the block-exit terminators that have no successor
+/// edges to style: a green border for `Return` and a red border for `Resume`,
+/// so normal returns and unwind re-raises stand out from ordinary blocks.
+fn node_style(terminator: &Terminator) -> &'static str {
+    match *terminator {
+        Terminator::Return => r#"color="darkgreen", penwidth="2", "#,
+        Terminator::Resume => r#"color="red", penwidth="2", "#,
+        _ => "",
+    }
+}
+
+/// The `bgcolor` for a block at the given loop-nesting depth: the default gray
+/// at depth 0, progressively darker grays as nesting increases.
+fn depth_color(depth: u32) -> String {
+    if depth == 0 {
+        return "gray".to_string();
+    }
+    let shade = 75u32.saturating_sub(depth * 15);
+    format!("gray{}", if shade < 20 { 20 } else { shade })
+}
+
+/// Render a span as a `file:line:col` string escaped for a *plain* DOT
+/// attribute value (`URL`/`tooltip`), i.e. only `"` and `\` are escaped.
+/// Graphviz does not un-escape these, so HTML entities would render literally —
+/// notably for macro-expansion spans like `<std macros>`.
+fn span_to_dot_string(tcx: &ty::TyCtxt, span: Span) -> String {
+    let raw = tcx.sess.codemap().span_to_string(span);
+    let mut escaped = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            '"' | '\\' => escaped.push('\\'),
+            _ => {}
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Build the `href`/`tooltip` attribute pair for a pseudo-HTML table cell that
+/// should link back to the given span. Cell attributes *are* HTML-like and
+/// graphviz un-escapes them, so entity-escaping is correct here.
+fn span_attrs(tcx: &ty::TyCtxt, span: Span) -> String {
+    let link = dot::escape_html(&tcx.sess.codemap().span_to_string(span));
+    format!(r#"href="{link}" tooltip="{link}""#, link=link)
+}
+
 /// Write graphviz DOT edges with labels between the given basic block and all of its successors.
-fn write_edges<W: Write>(source: BasicBlock, mir: &Mir, w: &mut W) -> io::Result<()> {
+fn write_edges<W: Write>(source: BasicBlock,
+                         mir: &Mir,
+                         reachable: &Option<HashSet<BasicBlock>>,
+                         w: &mut W) -> io::Result<()> {
     let terminator = &mir.basic_block_data(source).terminator();
     let labels = terminator.fmt_successor_labels();
 
+    // The cleanup/unwind edge, if this terminator has one, so panic-unwinding
+    // paths can be drawn distinctly from normal control flow. `Resume`/`Return`
+    // have no successors, so they contribute no edges to style here.
+    let unwind = unwind_target(terminator);
+
     for (&target, label) in terminator.successors().iter().zip(labels) {
-        try!(writeln!(w, r#"    {} -> {} [label="{}"];"#, node(source), node(target), label));
+        // Don't draw edges into blocks that were filtered out of the graph.
+        if !is_emitted(reachable, target) {
+            continue;
+        }
+        let style = if Some(target) == unwind {
+            r#", color="red", style="dashed""#
+        } else {
+            ""
+        };
+        try!(writeln!(w, r#"    {} -> {} [label="{}"{}];"#,
+                      node(source), node(target), label, style));
     }
 
     Ok(())
 }
 
+/// The unwind/cleanup successor of a terminator, if any. `Drop` and `Call` are
+/// the terminators that branch to a cleanup block when their operation panics.
+fn unwind_target(terminator: &Terminator) -> Option<BasicBlock> {
+    match *terminator {
+        Terminator::Drop { unwind, .. } => unwind,
+        Terminator::Call { cleanup, .. } => cleanup,
+        _ => None,
+    }
+}
+
 /// Write the graphviz DOT label for the overall graph. This is essentially a block of text that
 /// will appear below the graph, showing the type of the `fn` this MIR represents and the types of
 /// all the variables and temporaries.